@@ -0,0 +1,341 @@
+//! A disk-spilling LRU cache. Values are written to files under a
+//! directory, and the size limit is enforced against the total on-disk
+//! byte count rather than an in-memory measure. Restarting the process
+//! rescans the directory and rebuilds the LRU order from each file's
+//! modification time, so the cache resumes warm after a restart.
+
+use std::borrow::Borrow;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+use linked_hash_map::LinkedHashMap;
+
+use crate::meter::{CountableMeter, CountableMeterWithMeasure, Meter};
+
+/// Size limit based on the on-disk length of each cache entry's file.
+///
+/// Keyed on `String`, matching [`DiskCache`]'s file-name keys.
+pub struct FileSize;
+
+impl Meter<String, PathBuf> for FileSize {
+    type Measure = u64;
+
+    fn measure<Q: ?Sized>(&self, _: &Q, path: &PathBuf) -> u64
+    where
+        String: Borrow<Q>,
+    {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+impl CountableMeterWithMeasure<String, PathBuf, u64> for FileSize {
+    fn meter_add(&self, current: u64, amount: u64) -> u64 {
+        current + amount
+    }
+    fn meter_sub(&self, current: u64, amount: u64) -> u64 {
+        current.saturating_sub(amount)
+    }
+    fn meter_size(&self, current: u64) -> Option<u64> {
+        Some(current)
+    }
+}
+
+/// An LRU cache that spills its values to files under `dir`, evicting
+/// (deleting) the least-recently-used file once the total on-disk size
+/// exceeds `max_bytes`.
+///
+/// Each entry stores its file's size alongside its path, mirroring
+/// [`LruCache`][crate::LruCache]'s `(V, M::Measure)` tuple: that way
+/// `insert` and `evict` can account for a file's *previous* size without
+/// re-`stat`-ing a path that may already have been overwritten.
+pub struct DiskCache {
+    dir: PathBuf,
+    map: LinkedHashMap<String, (PathBuf, u64)>,
+    max_bytes: u64,
+    current_bytes: u64,
+    meter: FileSize,
+}
+
+impl DiskCache {
+    /// Opens (or creates) a disk cache rooted at `dir`, limited to
+    /// `max_bytes` of total file size. Any files already present in `dir`
+    /// are adopted into the cache, ordered oldest-to-newest by
+    /// modification time, so a process restart resumes with a warm,
+    /// correctly-sized cache.
+    pub fn open<P: Into<PathBuf>>(dir: P, max_bytes: u64) -> io::Result<DiskCache> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let meter = FileSize;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let key = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let mtime = entry.metadata()?.modified()?;
+            let size = meter.measure(&key, &path);
+            entries.push((key, path, mtime, size));
+        }
+        entries.sort_by_key(|&(_, _, mtime, _)| mtime);
+
+        let mut map = LinkedHashMap::new();
+        let mut current_bytes = 0;
+        for (key, path, _mtime, size) in entries {
+            current_bytes = meter.add(current_bytes, size);
+            map.insert(key, (path, size));
+        }
+
+        let mut cache = DiskCache {
+            dir,
+            map,
+            max_bytes,
+            current_bytes,
+            meter,
+        };
+        cache.evict();
+        Ok(cache)
+    }
+
+    /// Rejects keys that aren't a single, plain path component. `key` ends
+    /// up joined directly onto `self.dir` as a file name, so without this
+    /// check a key like `"../../etc/passwd"` would let `insert` write, and
+    /// `evict` delete, files outside the cache directory.
+    fn check_key(key: &str) -> io::Result<()> {
+        let mut components = Path::new(key).components();
+        let is_plain_component = !key.is_empty()
+            && key != "."
+            && key != ".."
+            && matches!(components.next(), Some(Component::Normal(_)))
+            && components.next().is_none();
+        if is_plain_component {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid cache key {:?}: must be a single path component", key),
+            ))
+        }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    /// Writes `value` to a file named `key`, evicting least-recently-used
+    /// entries until the cache's total on-disk size is back within
+    /// `max_bytes`.
+    pub fn insert(&mut self, key: String, value: &[u8]) -> io::Result<()> {
+        Self::check_key(&key)?;
+        let path = self.path_for(&key);
+        let tmp_path = path.with_extension("tmp");
+
+        // Write to a temp file and only `rename` it over `path` once the
+        // write has fully succeeded, and only then touch `self.map`/
+        // `current_bytes` -- otherwise a failed write (disk full,
+        // permission error, ...) could either truncate a previously
+        // committed file in place or leave a new file on disk that's
+        // never tracked (and so never evicted).
+        let write_result = File::create(&tmp_path).and_then(|mut f| f.write_all(value));
+        if let Err(e) = write_result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        fs::rename(&tmp_path, &path)?;
+
+        let old_size = self.map.remove(&key).map(|(_, size)| size);
+        if let Some(old_size) = old_size {
+            self.current_bytes = self.meter.sub(self.current_bytes, old_size);
+        }
+        let new_size = self.meter.measure(&key, &path);
+        self.current_bytes = self.meter.add(self.current_bytes, new_size);
+        self.map.insert(key, (path, new_size));
+
+        self.evict();
+        Ok(())
+    }
+
+    /// Opens the value for `key` for reading, refreshing its recency.
+    /// Returns `None` if `key` isn't in the cache.
+    ///
+    /// Returns a buffered file handle rather than a `Vec<u8>` so reading a
+    /// large cached blob doesn't require materializing the whole thing in
+    /// memory -- that would defeat the point of spilling it to disk.
+    pub fn get(&mut self, key: &str) -> io::Result<Option<BufReader<File>>> {
+        Self::check_key(key)?;
+        let path = match self.map.get_refresh(key) {
+            Some((path, _)) => path.clone(),
+            None => return Ok(None),
+        };
+        Ok(Some(BufReader::new(File::open(&path)?)))
+    }
+
+    /// Returns the total on-disk size, in bytes, of all cached entries.
+    pub fn size(&self) -> u64 {
+        self.current_bytes
+    }
+
+    /// Returns the number of entries currently in the cache.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn evict(&mut self) {
+        loop {
+            let size = self
+                .meter
+                .size(self.current_bytes)
+                .unwrap_or(self.current_bytes);
+            if size <= self.max_bytes {
+                break;
+            }
+            let (_, (path, size)) = match self.map.pop_front() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.current_bytes = self.meter.sub(self.current_bytes, size);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read as _;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A directory under the system temp dir, unique per test run, removed
+    /// on drop. `std::env::temp_dir` rather than a `tempfile` dependency,
+    /// since the rest of the crate only depends on `linked-hash-map`.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(tag: &str) -> TempDir {
+            static COUNTER: AtomicU32 = AtomicU32::new(0);
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let dir = std::env::temp_dir().join(format!(
+                "lru_time_cache-disk-test-{}-{}-{}",
+                std::process::id(),
+                tag,
+                n
+            ));
+            TempDir(dir)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_all(mut r: BufReader<File>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn insert_then_get_reads_back_the_value() {
+        let dir = TempDir::new("roundtrip");
+        let mut cache = DiskCache::open(dir.path(), 1024).unwrap();
+
+        cache.insert("a".to_string(), b"hello").unwrap();
+
+        let r = cache.get("a").unwrap().unwrap();
+        assert_eq!(read_all(r), b"hello");
+        assert_eq!(cache.size(), 5);
+    }
+
+    #[test]
+    fn insert_overwrite_replaces_rather_than_accumulates_size() {
+        let dir = TempDir::new("overwrite");
+        let mut cache = DiskCache::open(dir.path(), 1024).unwrap();
+
+        cache.insert("a".to_string(), b"12345").unwrap();
+        cache.insert("a".to_string(), b"1").unwrap();
+
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 1);
+        assert_eq!(read_all(cache.get("a").unwrap().unwrap()), b"1");
+    }
+
+    #[test]
+    fn insert_rejects_path_traversal_key() {
+        let dir = TempDir::new("traversal");
+        let mut cache = DiskCache::open(dir.path(), 1024).unwrap();
+
+        let err = cache.insert("../escape".to_string(), b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!dir.path().parent().unwrap().join("escape").exists());
+    }
+
+    #[test]
+    fn insert_evicts_lru_file_past_max_bytes() {
+        let dir = TempDir::new("evict");
+        let mut cache = DiskCache::open(dir.path(), 8).unwrap();
+
+        cache.insert("a".to_string(), b"12345").unwrap(); // 5 bytes
+        cache.insert("b".to_string(), b"12345").unwrap(); // 5 bytes, evicts "a"
+
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.path_for("a").exists());
+        assert!(cache.path_for("b").exists());
+        assert_eq!(cache.size(), 5);
+    }
+
+    #[test]
+    fn insert_failure_leaves_the_previous_entry_untouched() {
+        let dir = TempDir::new("write-failure");
+        let mut cache = DiskCache::open(dir.path(), 1024).unwrap();
+        cache.insert("a".to_string(), b"12345").unwrap();
+
+        // Block the temp-file write by pre-creating its path as a
+        // directory, forcing `File::create` to fail.
+        fs::create_dir(cache.path_for("a").with_extension("tmp")).unwrap();
+
+        cache.insert("a".to_string(), b"a much longer replacement").unwrap_err();
+
+        assert_eq!(cache.size(), 5);
+        assert_eq!(read_all(cache.get("a").unwrap().unwrap()), b"12345");
+    }
+
+    #[test]
+    fn open_rescans_directory_and_preserves_mtime_order() {
+        let dir = TempDir::new("rescan");
+        fs::create_dir_all(dir.path()).unwrap();
+        fs::write(dir.path().join("a"), b"12345").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        fs::write(dir.path().join("b"), b"12345").unwrap();
+
+        let mut cache = DiskCache::open(dir.path(), 10).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 10);
+
+        // At budget now; inserting a third entry must evict "a" (older
+        // mtime), proving `open` reconstructed LRU order from the
+        // pre-existing files rather than, say, directory iteration order.
+        cache.insert("c".to_string(), b"12345").unwrap();
+        assert!(!dir.path().join("a").exists());
+        assert!(dir.path().join("b").exists());
+        assert!(dir.path().join("c").exists());
+    }
+}
@@ -0,0 +1,358 @@
+//! A cache that holds a limited number of key-value pairs, evicting the
+//! least-recently-used entry once that limit is exceeded. "Limit" is
+//! pluggable: the default [`Count`] meter limits by number of entries, but
+//! any [`Meter`] can be supplied to limit by a custom measure (e.g. heap
+//! bytes).
+//!
+//! See the [`disk`] module for a persistent variant that spills values to
+//! files instead of keeping them in memory.
+
+extern crate linked_hash_map;
+
+mod meter;
+pub mod disk;
+
+pub use meter::{Count, CountableMeter, Meter};
+#[cfg(feature = "heapsize")]
+pub use meter::HeapSize;
+#[cfg(all(feature = "malloc_size_of", target_os = "linux"))]
+pub use meter::{MallocShallowSize, MallocSize};
+
+use linked_hash_map::LinkedHashMap;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+
+/// Initial entry capacity reserved for a cache built with
+/// [`LruCache::with_byte_capacity`], grown by doubling as entries are
+/// inserted.
+const INITIAL_BYTE_CAPACITY_ENTRIES: usize = 4;
+
+/// An LRU cache.
+///
+/// Each entry stores its own measure alongside its value, so that mutating
+/// a single entry (see [`get_mut`][LruCache::get_mut]) only has to
+/// recompute that entry's contribution to the cache's total size, not walk
+/// every entry.
+pub struct LruCache<K: Eq + Hash, V, M: Meter<K, V> = Count> {
+    map: LinkedHashMap<K, (V, M::Measure)>,
+    max_size: u64,
+    current_size: M::Measure,
+    meter: M,
+    /// Number of entries at which `insert` next doubles the map's capacity,
+    /// or `None` if `insert` leaves the map's capacity to the map itself.
+    next_entry_capacity: Option<usize>,
+}
+
+impl<K: Eq + Hash, V> LruCache<K, V, Count> {
+    /// Creates an empty cache that can hold at most `capacity` items.
+    pub fn new(capacity: usize) -> LruCache<K, V, Count> {
+        LruCache {
+            map: LinkedHashMap::with_capacity(capacity),
+            max_size: capacity as u64,
+            current_size: (),
+            meter: Count,
+            next_entry_capacity: None,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, M: CountableMeter<K, V>> LruCache<K, V, M> {
+    /// Creates an empty cache that can hold at most `capacity` as measured by `meter`.
+    pub fn with_meter_and_capacity(capacity: u64, meter: M) -> LruCache<K, V, M> {
+        LruCache {
+            map: LinkedHashMap::new(),
+            max_size: capacity,
+            current_size: Default::default(),
+            meter,
+            next_entry_capacity: None,
+        }
+    }
+
+    /// Creates an empty cache limited purely by `max_bytes`, as reported by
+    /// `meter`'s [`CountableMeter::size`]. The backing map isn't pre-sized to
+    /// `max_bytes` -- a byte budget says nothing about how many entries will
+    /// fit. Unlike [`with_meter_and_capacity`][1], which leaves the map's
+    /// growth to the map itself, it reserves a small initial entry capacity
+    /// and doubles it as inserts fill it up.
+    ///
+    /// [1]: LruCache::with_meter_and_capacity
+    pub fn with_byte_capacity(max_bytes: u64, meter: M) -> LruCache<K, V, M> {
+        LruCache {
+            map: LinkedHashMap::with_capacity(INITIAL_BYTE_CAPACITY_ENTRIES),
+            max_size: max_bytes,
+            current_size: Default::default(),
+            meter,
+            next_entry_capacity: Some(INITIAL_BYTE_CAPACITY_ENTRIES),
+        }
+    }
+
+    /// Returns the maximum size of the cache, as reported by `meter`.
+    pub fn capacity(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Returns the number of entries currently in the cache.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if the cache holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns `true` if the cache contains `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Returns a reference to the value for `key`, updating its recency.
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.map.get_refresh(key).map(|entry| &entry.0)
+    }
+
+    /// Returns a guard granting mutable access to the value for `key`,
+    /// updating its recency. Like [`get`][LruCache::get], this is not
+    /// limited to meters with `Measure = ()`: the returned [`ValueMut`]
+    /// re-measures the entry when it's dropped, adjusts the cache's running
+    /// size by the delta, and evicts if the mutation pushed the cache over
+    /// `capacity()`.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<ValueMut<'_, K, V, M>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash + ToOwned<Owned = K>,
+    {
+        self.map.get_refresh(key)?;
+        Some(ValueMut {
+            cache: self,
+            key: key.to_owned(),
+        })
+    }
+
+    /// Calls `f` with every key and a mutable reference to its value, then
+    /// remeasures every entry and evicts if needed.
+    ///
+    /// This plays the role of `iter_mut`: yielding one remeasure-on-drop
+    /// guard per entry isn't expressible as a safe standard `Iterator`
+    /// (the `Item` can't borrow from `&mut self` across calls to `next`),
+    /// so instead the whole cache is remeasured once after `f` has run
+    /// over every entry.
+    pub fn iter_mut<F: FnMut(&K, &mut V)>(&mut self, mut f: F) {
+        for (k, entry) in self.map.iter_mut() {
+            f(k, &mut entry.0);
+        }
+        self.recompute_size();
+    }
+
+    fn recompute_size(&mut self) {
+        let mut total = Default::default();
+        for (k, entry) in self.map.iter_mut() {
+            let measure = self.meter.measure(k, &entry.0);
+            entry.1 = measure;
+            total = self.meter.add(total, measure);
+        }
+        self.current_size = total;
+        self.evict();
+    }
+
+    /// Removes the entry for `key`, returning its value if present.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        let old = self.map.remove(key);
+        if let Some((_, measure)) = &old {
+            self.current_size = self.meter.sub(self.current_size, *measure);
+        }
+        old.map(|(v, _)| v)
+    }
+
+    /// Removes and returns the least-recently-used entry, if any.
+    pub fn remove_lru(&mut self) -> Option<(K, V)> {
+        let front = self.map.pop_front();
+        if let Some((_, (_, measure))) = &front {
+            self.current_size = self.meter.sub(self.current_size, *measure);
+        }
+        front.map(|(k, (v, _))| (k, v))
+    }
+
+    /// Inserts a key-value pair into the cache, returning the old value for
+    /// `key` if it was already present. The least-recently-used entries are
+    /// evicted until the cache's size, as reported by `meter`, is within
+    /// `capacity()`.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let new_size = self.meter.measure(&k, &v);
+        self.current_size = self.meter.add(self.current_size, new_size);
+
+        let old = self.map.remove(&k);
+        if let Some((_, old_size)) = &old {
+            self.current_size = self.meter.sub(self.current_size, *old_size);
+        }
+
+        if let Some(next_capacity) = self.next_entry_capacity {
+            if self.map.len() >= next_capacity {
+                self.map.reserve(next_capacity);
+                self.next_entry_capacity = Some(next_capacity * 2);
+            }
+        }
+
+        self.map.insert(k, (v, new_size));
+        self.evict();
+        old.map(|(v, _)| v)
+    }
+
+    fn evict(&mut self) {
+        let max_size = self.max_size;
+        loop {
+            // A `None` size means the meter can't report one (e.g. `Count`);
+            // fall back to the number of entries as the size.
+            let size = self
+                .meter
+                .size(self.current_size)
+                .unwrap_or_else(|| self.map.len() as u64);
+            if size <= max_size || self.remove_lru().is_none() {
+                break;
+            }
+        }
+    }
+}
+
+/// A handle granting mutable access to a [`LruCache`] entry, returned by
+/// [`LruCache::get_mut`].
+///
+/// Dropping the guard re-measures the entry with the cache's [`Meter`],
+/// updates the cache's running size by the delta against the
+/// previously-stored measure, and evicts least-recently-used entries if the
+/// mutation pushed the cache over capacity.
+pub struct ValueMut<'a, K: 'a + Eq + Hash, V: 'a, M: 'a + CountableMeter<K, V>> {
+    cache: &'a mut LruCache<K, V, M>,
+    key: K,
+}
+
+impl<'a, K: Eq + Hash, V, M: CountableMeter<K, V>> Deref for ValueMut<'a, K, V, M> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self
+            .cache
+            .map
+            .get(&self.key)
+            .expect("key present while ValueMut is alive")
+            .0
+    }
+}
+
+impl<'a, K: Eq + Hash, V, M: CountableMeter<K, V>> DerefMut for ValueMut<'a, K, V, M> {
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self
+            .cache
+            .map
+            .get_mut(&self.key)
+            .expect("key present while ValueMut is alive")
+            .0
+    }
+}
+
+impl<'a, K: Eq + Hash, V, M: CountableMeter<K, V>> Drop for ValueMut<'a, K, V, M> {
+    fn drop(&mut self) {
+        let cache = &mut *self.cache;
+
+        let old_measure = match cache.map.get(&self.key) {
+            Some(&(_, measure)) => measure,
+            None => return,
+        };
+        let new_measure = {
+            let value = &cache.map.get(&self.key).unwrap().0;
+            cache.meter.measure(&self.key, value)
+        };
+
+        if let Some(entry) = cache.map.get_mut(&self.key) {
+            entry.1 = new_measure;
+        }
+        cache.current_size = cache.meter.sub(cache.current_size, old_measure);
+        cache.current_size = cache.meter.add(cache.current_size, new_measure);
+        cache.evict();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Meter` that measures a `String` value by its byte length, for
+    /// exercising `with_byte_capacity` and `get_mut`/`iter_mut` eviction
+    /// without pulling in the `heapsize`/`malloc_size_of` features.
+    struct ByteLen;
+
+    impl<K> Meter<K, String> for ByteLen {
+        type Measure = usize;
+
+        fn measure<Q: ?Sized>(&self, _: &Q, value: &String) -> usize
+        where
+            K: Borrow<Q>,
+        {
+            value.len()
+        }
+    }
+
+    #[test]
+    fn with_byte_capacity_evicts_lru_until_under_budget() {
+        let mut cache: LruCache<&str, String, ByteLen> = LruCache::with_byte_capacity(12, ByteLen);
+        cache.insert("a", "12345".to_string()); // 5 bytes
+        cache.insert("b", "12345".to_string()); // 5 bytes, total 10
+        cache.insert("c", "123456".to_string()); // 6 bytes, total would be 16
+
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.contains_key("a"));
+        assert!(cache.contains_key("b"));
+        assert!(cache.contains_key("c"));
+    }
+
+    #[test]
+    fn get_mut_evicts_lru_once_growth_exceeds_budget() {
+        let mut cache: LruCache<&str, String, ByteLen> = LruCache::with_byte_capacity(12, ByteLen);
+        cache.insert("a", "12345".to_string()); // 5 bytes
+        cache.insert("b", "1234567".to_string()); // 7 bytes, total 12
+
+        cache.get_mut(&"a").unwrap().push_str("xx"); // grows "a" to 7 bytes, total 14
+
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.contains_key("b"));
+        assert!(cache.contains_key("a"));
+    }
+
+    #[test]
+    fn get_mut_borrows_like_get_instead_of_requiring_an_owned_key() {
+        let mut cache: LruCache<String, String, ByteLen> =
+            LruCache::with_byte_capacity(1024, ByteLen);
+        cache.insert("a".to_string(), "12345".to_string());
+
+        // `&str`, not `&String`, mirroring `get`/`remove`/`contains_key`.
+        cache.get_mut("a").unwrap().push_str("xx");
+
+        assert_eq!(cache.get("a").unwrap(), "12345xx");
+    }
+
+    #[test]
+    fn iter_mut_remeasures_every_entry_and_evicts() {
+        let mut cache: LruCache<&str, String, ByteLen> = LruCache::with_byte_capacity(12, ByteLen);
+        cache.insert("a", "12345".to_string()); // 5 bytes
+        cache.insert("b", "12345".to_string()); // 5 bytes, total 10
+
+        cache.iter_mut(|_, v| v.push_str("xx")); // grows both to 7 bytes, total 14
+
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.contains_key("a"));
+        assert!(cache.contains_key("b"));
+    }
+}
@@ -1,6 +1,12 @@
 #[cfg(feature = "heapsize")]
 extern crate heapsize;
 
+#[cfg(feature = "malloc_size_of")]
+extern crate malloc_size_of;
+
+#[cfg(feature = "malloc_size_of")]
+extern crate libc;
+
 use std::borrow::Borrow;
 
 /// A trait for measuring the size of a cache entry.
@@ -125,3 +131,112 @@ mod heap_meter {
 
 #[cfg(feature = "heapsize")]
 pub use heap_meter::*;
+
+#[cfg(all(feature = "malloc_size_of", target_os = "linux"))]
+mod malloc_meter {
+    use malloc_size_of::{MallocShallowSizeOf, MallocSizeOf, MallocSizeOfOps};
+    use std::borrow::Borrow;
+    use std::os::raw::c_void;
+
+    /// Size limit based on the allocator-reported size of each cache item.
+    ///
+    /// Unlike [`HeapSize`][1], which sums `size_of` recursively over owned
+    /// fields, `MallocSize` asks the allocator how many bytes a pointer
+    /// actually occupies (via the system allocator's `malloc_usable_size`), so
+    /// the reported size matches real allocated bytes.
+    ///
+    /// Requires cache entries that implement [`MallocSizeOf`][2].
+    ///
+    /// [1]: struct.HeapSize.html
+    /// [2]: https://docs.rs/malloc_size_of
+    pub struct MallocSize;
+
+    impl<K, V: MallocSizeOf> super::Meter<K, V> for MallocSize {
+        type Measure = usize;
+
+        fn measure<Q: ?Sized>(&self, _: &Q, item: &V) -> usize
+        where
+            K: Borrow<Q>,
+        {
+            let mut ops = usable_size_ops();
+            ::std::mem::size_of::<V>() + item.size_of(&mut ops)
+        }
+    }
+
+    /// Size limit for cache items that can only report their own top-level
+    /// allocation, leaving the caller to iterate into owned heap data if a
+    /// deeper measurement is required.
+    ///
+    /// Requires cache entries that implement [`MallocShallowSizeOf`][1].
+    ///
+    /// [1]: https://docs.rs/malloc_size_of
+    pub struct MallocShallowSize;
+
+    impl<K, V: MallocShallowSizeOf> super::Meter<K, V> for MallocShallowSize {
+        type Measure = usize;
+
+        fn measure<Q: ?Sized>(&self, _: &Q, item: &V) -> usize
+        where
+            K: Borrow<Q>,
+        {
+            let mut ops = usable_size_ops();
+            ::std::mem::size_of::<V>() + item.shallow_size_of(&mut ops)
+        }
+    }
+
+    /// Build `MallocSizeOfOps` around the platform allocator's usable-size
+    /// query, so `measure` reports real allocated bytes rather than relying
+    /// on `size_of` alone.
+    ///
+    /// `malloc_size_of` is allocator-agnostic -- it expects the embedder to
+    /// supply the usable-size function rather than exporting one itself --
+    /// so this reaches into `libc::malloc_usable_size` directly. That's a
+    /// glibc extension, so `MallocSize`/`MallocShallowSize` are only
+    /// available on Linux; swap this for `jemalloc_usable_size` (via
+    /// `jemalloc-sys`) if the process uses jemalloc instead of the system
+    /// allocator.
+    fn usable_size_ops() -> MallocSizeOfOps {
+        unsafe extern "C" fn usable_size(ptr: *const c_void) -> usize {
+            libc::malloc_usable_size(ptr as *mut c_void)
+        }
+
+        MallocSizeOfOps::new(usable_size, None, None)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{usable_size_ops, MallocShallowSize, MallocSize};
+        use crate::meter::Meter;
+        use malloc_size_of::MallocShallowSizeOf;
+
+        /// `MallocSizeOf`/`MallocShallowSizeOf` are implemented upstream for
+        /// `Vec<T>`, so a `Vec<String>` exercises both the deep (`MallocSize`,
+        /// which recurses into each `String`'s own allocation) and shallow
+        /// (`MallocShallowSize`, which only measures the `Vec`'s own backing
+        /// buffer) meters without needing a throwaway type of our own.
+        #[test]
+        fn malloc_size_measures_the_vec_and_its_elements() {
+            let value: Vec<String> = vec!["hello".to_string(), "world!!".to_string()];
+            let key = "key".to_string();
+
+            let deep = Meter::<String, Vec<String>>::measure(&MallocSize, &key, &value);
+            let shallow = Meter::<String, Vec<String>>::measure(&MallocShallowSize, &key, &value);
+
+            assert!(
+                deep > shallow,
+                "deep measure ({}) should exceed the Vec's own shallow allocation ({})",
+                deep,
+                shallow
+            );
+
+            // Sanity-check against the raw allocator query used under the hood.
+            let mut ops = usable_size_ops();
+            let expected_shallow =
+                ::std::mem::size_of::<Vec<String>>() + value.shallow_size_of(&mut ops);
+            assert_eq!(shallow, expected_shallow);
+        }
+    }
+}
+
+#[cfg(all(feature = "malloc_size_of", target_os = "linux"))]
+pub use malloc_meter::*;